@@ -1,12 +1,97 @@
 extern crate termion;
 extern crate indoc;
 
-use termion::event::Key;
-use termion::input::TermRead;
+use termion::event::{Event, Key, MouseButton, MouseEvent};
+use termion::input::{MouseTerminal, TermRead};
 use termion::raw::IntoRawMode;
-use termion::cursor::DetectCursorPos;
+use termion::screen::AlternateScreen;
+use termion::color;
 use std::io::Write;
 
+// Owns the terminal state. The inner wrappers already restore cooked mode,
+// exit mouse reporting and leave the alternate screen on their own `Drop`;
+// this type only has to put the cursor shape back on top of that, so a
+// clean quit and a panic unwind both leave the shell exactly as they found it.
+struct Terminal(AlternateScreen<MouseTerminal<termion::raw::RawTerminal<std::io::Stdout>>>);
+
+// The one live Terminal, so the panic hook can reach it. Set once the
+// Terminal is boxed (and thus at a stable address) by `App::new`, and
+// cleared by `Terminal::drop` so the hook never follows a dangling pointer.
+static ACTIVE_TERMINAL: std::sync::atomic::AtomicPtr<Terminal> =
+    std::sync::atomic::AtomicPtr::new(std::ptr::null_mut());
+
+impl Terminal {
+    fn new() -> Result<Terminal, std::io::Error> {
+        let raw = std::io::stdout().into_raw_mode()?;
+        let mouse = MouseTerminal::from(raw);
+        Ok(Terminal(AlternateScreen::from(mouse)))
+    }
+
+    // Leaves raw mode (ICANON/OPOST/echo back on) without undoing the rest
+    // of the terminal setup, so a panic's backtrace prints normally.
+    fn suspend_raw_mode(&self) -> std::io::Result<()> {
+        self.0.suspend_raw_mode()
+    }
+}
+
+impl Write for Terminal {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.0.flush()
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = ACTIVE_TERMINAL.compare_exchange(
+            self as *mut Terminal,
+            std::ptr::null_mut(),
+            std::sync::atomic::Ordering::SeqCst,
+            std::sync::atomic::Ordering::SeqCst,
+        );
+        let _ = write!(self.0, "{}", termion::cursor::SteadyBlock);
+        let _ = self.0.flush();
+    }
+}
+
+// Leaves raw mode and the alternate screen and shows the cursor before
+// handing off to the default hook, so a panic's backtrace prints normally
+// on the user's real screen instead of staircasing over the board.
+fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic_info| {
+        let active = ACTIVE_TERMINAL.load(std::sync::atomic::Ordering::SeqCst);
+        // Safety: `active` is either null or was set by `App::new` to point
+        // at the `Box<Terminal>` owned by the `App`, and is cleared by
+        // `Terminal::drop` before that box goes away.
+        if let Some(terminal) = unsafe { active.as_ref() } {
+            let _ = terminal.suspend_raw_mode();
+        }
+        print!("{}{}{}",
+               termion::screen::ToMainScreen,
+               termion::cursor::Show,
+               termion::cursor::SteadyBlock);
+        let _ = std::io::stdout().flush();
+        default_hook(panic_info);
+    }));
+}
+
+// Foreground colors cycled by the number keys 1-9
+const PALETTE: [color::Rgb; 9] = [
+    color::Rgb(255, 255, 255), // 1: white
+    color::Rgb(255, 0, 0),     // 2: red
+    color::Rgb(0, 255, 0),     // 3: green
+    color::Rgb(0, 128, 255),   // 4: blue
+    color::Rgb(255, 255, 0),   // 5: yellow
+    color::Rgb(255, 0, 255),   // 6: magenta
+    color::Rgb(0, 255, 255),   // 7: cyan
+    color::Rgb(255, 128, 0),   // 8: orange
+    color::Rgb(128, 0, 255),   // 9: purple
+];
+
 const MANUAL: &str = indoc::indoc! {r#"
                       .   '||      '||
               ....  .||.   || ...   || ...
@@ -31,10 +116,30 @@ const MANUAL: &str = indoc::indoc! {r#"
     hjkl:    Move the cursor. Hold shift to move fast
     c:       Clear the entire screen
     i:       Enter insert mode
+    dfDF:    Pick the brush glyph used by click-and-drag
+    1-9:     Pick the pen's foreground color
+    b:       Toggle a background fill in the pen color
+    Mouse:   Left-click or drag to paint with the brush,
+             right-click to erase
+    ::       Enter command mode
+    v:       Enter visual mode
 
     ============= Insert mode commands ==============
     Ctrl-[ or ESC:     Go back to normal mode
     other keys:        type stuff
+
+    ============= Command mode commands =============
+    w path:  Save the drawing to a file
+    e path:  Load a drawing from a file
+    Ctrl-[ or ESC:     Cancel and go back to normal mode
+
+    ============= Visual mode commands ===============
+    hjkl:    Grow or shrink the selection rectangle
+    fd:      Fill the selection with the brush glyph
+    x or space:        Erase the selection
+    y:       Copy the selection
+    p:       Paste the copied selection at the cursor
+    v or Ctrl-[ or ESC: Go back to normal mode
 "#};
 
 fn get_text_shape(text: &str) -> (u16, u16) {
@@ -43,26 +148,337 @@ fn get_text_shape(text: &str) -> (u16, u16) {
     (width, height)
 }
 
+// A single painted glyph, with the pen it was painted with. Keeping the
+// color here (rather than just the char) is what lets a colored cell be
+// redrawn faithfully later, e.g. when Visual mode's border is lifted.
+#[derive(Clone, Copy)]
+struct Cell {
+    ch: char,
+    fg: color::Rgb,
+    bg: Option<color::Rgb>,
+}
+
+impl Default for Cell {
+    fn default() -> Cell {
+        Cell { ch: ' ', fg: color::Rgb(255, 255, 255), bg: None }
+    }
+}
+
+// Mirrors every glyph painted, pasted or loaded onto the board, keyed by
+// the absolute cursor position it was written at. The manual, the status
+// line and the Visual-mode outline are not recorded. This is what makes
+// the board save-able, and later reloadable or redrawable.
+struct Canvas {
+    cells: std::collections::HashMap<(u16, u16), Cell>,
+}
+
+impl Canvas {
+    fn new() -> Canvas {
+        Canvas { cells: std::collections::HashMap::new() }
+    }
+
+    // Parse plain text (as produced by `to_text`) back into a Canvas. Loaded
+    // cells get a plain white pen, since the file format doesn't carry color.
+    fn from_text(text: &str) -> Canvas {
+        let mut canvas = Canvas::new();
+        for (row_index, line) in text.lines().enumerate() {
+            let y = row_index as u16 + 1;
+            for (col_index, ch) in line.chars().enumerate() {
+                if ch != ' ' {
+                    let x = col_index as u16 + 1;
+                    canvas.set((x, y), Cell { ch, ..Cell::default() });
+                }
+            }
+        }
+        canvas
+    }
+
+    fn set(&mut self, pos: (u16, u16), cell: Cell) {
+        self.cells.insert(pos, cell);
+    }
+
+    fn get(&self, pos: (u16, u16)) -> Cell {
+        self.cells.get(&pos).copied().unwrap_or_default()
+    }
+
+    fn clear(&mut self) {
+        self.cells.clear();
+    }
+
+    fn bounds(&self) -> Option<(u16, u16)> {
+        self.cells.keys().fold(None, |bounds, &(x, y)| match bounds {
+            None => Some((x, y)),
+            Some((max_x, max_y)) => Some((std::cmp::max(max_x, x), std::cmp::max(max_y, y))),
+        })
+    }
+
+    // Render the canvas as plain text, one line per row, for `:w`. Every
+    // line is padded out to the canvas's full width so reloading it lands
+    // cells back at the same positions (see `from_text`).
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        if let Some((max_x, max_y)) = self.bounds() {
+            for y in 1..=max_y {
+                let line: String = (1..=max_x)
+                    .map(|x| self.cells.get(&(x, y)).map(|c| c.ch).unwrap_or(' '))
+                    .collect();
+                text.push_str(&line);
+                text.push('\n');
+            }
+        }
+        text
+    }
+}
+
 enum Mode {
     Normal,
     Insert {
         entrance: (u16, u16),
     },
+    Command {
+        buffer: String,
+    },
+    Visual {
+        anchor: (u16, u16),
+    },
+}
+
+// A rectangle given by its (min_x, min_y) and (max_x, max_y) corners, both inclusive
+type Rect = ((u16, u16), (u16, u16));
+
+fn rect_of(anchor: (u16, u16), cursor: (u16, u16)) -> Rect {
+    let min = (std::cmp::min(anchor.0, cursor.0), std::cmp::min(anchor.1, cursor.1));
+    let max = (std::cmp::max(anchor.0, cursor.0), std::cmp::max(anchor.1, cursor.1));
+    (min, max)
+}
+
+// The cells a `:e` load should write, with their own plain-white color —
+// never the caller's live pen. Takes no pen/App state at all, so `e`'s
+// consumer (`App::load_from_file`) can't accidentally paint with the
+// active pen the way `self.paint()` would.
+fn load_cells(contents: &str) -> Vec<((u16, u16), Cell)> {
+    Canvas::from_text(contents).cells.into_iter().collect()
+}
+
+// Splits a typed command like "w board.txt" into its verb and argument
+fn parse_command(command: &str) -> Option<(&str, &str)> {
+    let mut parts = command.splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(verb), Some(arg)) => Some((verb, arg)),
+        _ => None,
+    }
 }
 
 struct App {
-    raw_terminal: termion::raw::RawTerminal<std::io::Stdout>,
+    // Boxed so its address is stable once `new` registers it with
+    // `ACTIVE_TERMINAL` for the panic hook to find.
+    terminal: Box<Terminal>,
     mode: Mode,
+    brush: &'static str,
+    pen: (color::Rgb, Option<color::Rgb>),
+    canvas: Canvas,
+    // Where the terminal's own cursor is right now. The app moves the
+    // cursor only through the methods below, which keep this in sync, so
+    // nothing ever needs to ask the terminal where the cursor landed.
+    cursor: (u16, u16),
+    // The outline drawn for Visual mode, so it can be restored from the
+    // canvas before the rectangle moves or the mode is left.
+    visual_border: Vec<(u16, u16)>,
+    clipboard: Vec<Vec<Cell>>,
 }
 
 impl App {
     fn new() -> Result<App, std::io::Error> {
+        let terminal = Box::new(Terminal::new()?);
+        ACTIVE_TERMINAL.store(
+            terminal.as_ref() as *const Terminal as *mut Terminal,
+            std::sync::atomic::Ordering::SeqCst,
+        );
         Ok(App {
             mode: Mode::Normal,
-            raw_terminal: std::io::stdout().into_raw_mode()?,
+            terminal,
+            brush: "▘",
+            pen: (PALETTE[0], None),
+            canvas: Canvas::new(),
+            cursor: (1, 1),
+            visual_border: Vec::new(),
+            clipboard: Vec::new(),
         })
     }
 
+    // Move the terminal's cursor to `pos` and remember it.
+    fn goto(&mut self, pos: (u16, u16)) -> Result<(), std::io::Error> {
+        write!(self.terminal, "{}", termion::cursor::Goto(pos.0, pos.1))?;
+        self.cursor = pos;
+        Ok(())
+    }
+
+    // Account for the terminal's own cursor-advance-and-wrap after a
+    // visible character is printed, so `self.cursor` keeps tracking the
+    // real cursor without ever having to ask the terminal for it.
+    fn advance_cursor(&mut self) -> Result<(), std::io::Error> {
+        let (cols, _) = termion::terminal_size()?;
+        if self.cursor.0 >= cols {
+            self.cursor = (1, self.cursor.1 + 1);
+        } else {
+            self.cursor.0 += 1;
+        }
+        Ok(())
+    }
+
+    // Write a single colored glyph at the cursor, then reset so other
+    // writes stay uncolored. Doesn't touch the canvas or `self.cursor`;
+    // callers that paint onto the board use `paint`, below.
+    fn write_glyph(&mut self, ch: char, fg: color::Rgb, bg: Option<color::Rgb>) -> Result<(), std::io::Error> {
+        write!(self.terminal, "{}", color::Fg(fg))?;
+        if let Some(bg) = bg {
+            write!(self.terminal, "{}", color::Bg(bg))?;
+        }
+        write!(self.terminal, "{}", ch)?;
+        if bg.is_some() {
+            write!(self.terminal, "{}", color::Bg(color::Reset))?;
+        }
+        write!(self.terminal, "{}", color::Fg(color::Reset))?;
+        Ok(())
+    }
+
+    // Write a glyph or character with the current pen, record it (with its
+    // color) in the canvas at the position it landed on, and advance the
+    // cursor like the terminal does after printing a visible character.
+    fn paint(&mut self, s: &str) -> Result<(), std::io::Error> {
+        if let Some(ch) = s.chars().next() {
+            let pos = self.cursor;
+            self.write_glyph(ch, self.pen.0, self.pen.1)?;
+            self.canvas.set(pos, Cell { ch, fg: self.pen.0, bg: self.pen.1 });
+            self.advance_cursor()?;
+        }
+        Ok(())
+    }
+
+    // Redraw whatever the canvas says belongs at `pos`, in its original
+    // color, without disturbing the pen or recording anything new.
+    fn restore_cell(&mut self, pos: (u16, u16)) -> Result<(), std::io::Error> {
+        let cell = self.canvas.get(pos);
+        self.goto(pos)?;
+        self.write_glyph(cell.ch, cell.fg, cell.bg)
+    }
+
+    fn move_cursor(&mut self, key: Key) -> Result<(), std::io::Error> {
+        let (cols, rows) = termion::terminal_size()?;
+        match key {
+            Key::Char('h') => { write!(self.terminal, "{}", termion::cursor::Left(1))?; self.cursor.0 = self.cursor.0.saturating_sub(1).max(1); }
+            Key::Char('l') => { write!(self.terminal, "{}", termion::cursor::Right(1))?; self.cursor.0 = (self.cursor.0 + 1).min(cols); }
+            Key::Char('k') => { write!(self.terminal, "{}", termion::cursor::Up(1))?; self.cursor.1 = self.cursor.1.saturating_sub(1).max(1); }
+            Key::Char('j') => { write!(self.terminal, "{}", termion::cursor::Down(1))?; self.cursor.1 = (self.cursor.1 + 1).min(rows); }
+            Key::Char('H') => { write!(self.terminal, "{}", termion::cursor::Left(8))?; self.cursor.0 = self.cursor.0.saturating_sub(8).max(1); }
+            Key::Char('L') => { write!(self.terminal, "{}", termion::cursor::Right(8))?; self.cursor.0 = (self.cursor.0 + 8).min(cols); }
+            Key::Char('K') => { write!(self.terminal, "{}", termion::cursor::Up(6))?; self.cursor.1 = self.cursor.1.saturating_sub(6).max(1); }
+            Key::Char('J') => { write!(self.terminal, "{}", termion::cursor::Down(6))?; self.cursor.1 = (self.cursor.1 + 6).min(rows); }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // The rectangle spanned by `anchor` and the live cursor position
+    fn visual_rect(&self, anchor: (u16, u16)) -> Rect {
+        rect_of(anchor, self.cursor)
+    }
+
+    // Puts back whatever the canvas says belongs at the outline's cells,
+    // then returns the real cursor to where the app thinks it is.
+    fn restore_visual_border(&mut self) -> Result<(), std::io::Error> {
+        let back_to = self.cursor;
+        for pos in std::mem::take(&mut self.visual_border) {
+            self.restore_cell(pos)?;
+        }
+        self.goto(back_to)?;
+        self.terminal.flush()?;
+        Ok(())
+    }
+
+    fn draw_visual_border(&mut self, anchor: (u16, u16)) -> Result<(), std::io::Error> {
+        self.restore_visual_border()?;
+        let back_to = self.cursor;
+        let (min, max) = self.visual_rect(anchor);
+        let mut border = Vec::new();
+        for x in min.0..=max.0 {
+            for &y in &[min.1, max.1] {
+                write!(self.terminal, "{}▒", termion::cursor::Goto(x, y))?;
+                border.push((x, y));
+            }
+        }
+        for y in min.1..=max.1 {
+            for &x in &[min.0, max.0] {
+                write!(self.terminal, "{}▒", termion::cursor::Goto(x, y))?;
+                border.push((x, y));
+            }
+        }
+        self.goto(back_to)?;
+        self.terminal.flush()?;
+        self.visual_border = border;
+        Ok(())
+    }
+
+    fn fill_visual_rect(&mut self, anchor: (u16, u16), glyph: &str) -> Result<(), std::io::Error> {
+        self.restore_visual_border()?;
+        let back_to = self.cursor;
+        let (min, max) = self.visual_rect(anchor);
+        let glyph = glyph.to_owned();
+        for y in min.1..=max.1 {
+            for x in min.0..=max.0 {
+                self.goto((x, y))?;
+                self.paint(&glyph)?;
+            }
+        }
+        self.goto(back_to)?;
+        self.draw_visual_border(anchor)
+    }
+
+    fn yank_visual_rect(&mut self, anchor: (u16, u16)) -> Result<(), std::io::Error> {
+        let (min, max) = self.visual_rect(anchor);
+        self.clipboard = (min.1..=max.1)
+            .map(|y| (min.0..=max.0).map(|x| self.canvas.get((x, y))).collect())
+            .collect();
+        Ok(())
+    }
+
+    fn paste_clipboard(&mut self) -> Result<(), std::io::Error> {
+        let origin = self.cursor;
+        let clipboard = self.clipboard.clone();
+        for (row_index, row) in clipboard.iter().enumerate() {
+            let y = origin.1 + row_index as u16;
+            for (col_index, &cell) in row.iter().enumerate() {
+                let x = origin.0 + col_index as u16;
+                self.goto((x, y))?;
+                // Paste with each cell's own color, not the active pen.
+                self.write_glyph(cell.ch, cell.fg, cell.bg)?;
+                self.canvas.set((x, y), cell);
+            }
+        }
+        self.goto(origin)?;
+        Ok(())
+    }
+
+    // Write the canvas to `path` as plain text, for the `:w` command
+    fn save_to_file(&mut self, path: &str) -> Result<(), std::io::Error> {
+        std::fs::write(path, self.canvas.to_text())
+    }
+
+    // Load `path` by replaying its non-space characters as Goto + char writes
+    fn load_from_file(&mut self, path: &str) -> Result<(), std::io::Error> {
+        let contents = std::fs::read_to_string(path)?;
+        let origin = self.cursor;
+        for (pos, cell) in load_cells(&contents) {
+            self.goto(pos)?;
+            // Paint with the loaded cell's own color, not the active pen.
+            self.write_glyph(cell.ch, cell.fg, cell.bg)?;
+            self.canvas.set(pos, cell);
+        }
+        self.goto(origin)?;
+        self.terminal.flush()?;
+        Ok(())
+    }
+
     fn show_manual(&mut self) -> Result<(), std::io::Error> {
         let terminal_size = termion::terminal_size()?;
 
@@ -87,103 +503,283 @@ impl App {
 
         for (index, line) in manual.lines().enumerate() {
             let row = manual_origin.1 + (index as u16);
-            write!(self.raw_terminal, "{}{}",
+            write!(self.terminal, "{}{}",
                    termion::cursor::Goto(manual_origin.0, row),
                    line)?;
         }
-        write!(self.raw_terminal, "{}", termion::cursor::Goto(cursor_absolute.0, cursor_absolute.1))?;
-        self.raw_terminal.flush()?;
+        self.goto(cursor_absolute)?;
+        self.terminal.flush()?;
 
         Ok(())
     }
 
-    fn handle_input(&mut self, key: Key) -> Result<bool, std::io::Error> {
+    fn handle_key(&mut self, key: Key) -> Result<bool, std::io::Error> {
         match self.mode {
             Mode::Normal => match key {
 
                 // Movement
-                Key::Char('h') => write!(self.raw_terminal, "{}", termion::cursor::Left(1))?,
-                Key::Char('l') => write!(self.raw_terminal, "{}", termion::cursor::Right(1))?,
-                Key::Char('k') => write!(self.raw_terminal, "{}", termion::cursor::Up(1))?,
-                Key::Char('j') => write!(self.raw_terminal, "{}", termion::cursor::Down(1))?,
-                Key::Char('H') => write!(self.raw_terminal, "{}", termion::cursor::Left(8))?,
-                Key::Char('L') => write!(self.raw_terminal, "{}", termion::cursor::Right(8))?,
-                Key::Char('K') => write!(self.raw_terminal, "{}", termion::cursor::Up(6))?,
-                Key::Char('J') => write!(self.raw_terminal, "{}", termion::cursor::Down(6))?,
+                Key::Char('h') | Key::Char('l') | Key::Char('k') | Key::Char('j') |
+                Key::Char('H') | Key::Char('L') | Key::Char('K') | Key::Char('J') => self.move_cursor(key)?,
 
                 // Experimental
-                Key::Char('d') => write!(self.raw_terminal, "{}", "▘")?,
-                Key::Char('f') => write!(self.raw_terminal, "{}", "▖")?,
-                Key::Char('D') => write!(self.raw_terminal, "{}", "▀")?,
-                Key::Char('F') => write!(self.raw_terminal, "{}", "▄")?,
+                Key::Char('d') => { self.brush = "▘"; self.paint(self.brush)? }
+                Key::Char('f') => { self.brush = "▖"; self.paint(self.brush)? }
+                Key::Char('D') => { self.brush = "▀"; self.paint(self.brush)? }
+                Key::Char('F') => { self.brush = "▄"; self.paint(self.brush)? }
+
+                // Pen color
+                Key::Char(digit @ '1'..='9') => {
+                    self.pen.0 = PALETTE[digit.to_digit(10).unwrap() as usize - 1];
+                }
+                Key::Char('b') => {
+                    self.pen.1 = match self.pen.1 {
+                        Some(_) => None,
+                        None => Some(self.pen.0),
+                    };
+                }
 
                 // Erasing
-                Key::Char(' ') => write!(self.raw_terminal, "{}", " ")?,
-                Key::Char('c') => write!(self.raw_terminal, "{}", termion::clear::All)?,
+                Key::Char(' ') => self.paint(" ")?,
+                Key::Char('c') => {
+                    write!(self.terminal, "{}", termion::clear::All)?;
+                    self.canvas.clear();
+                }
 
                 // State changes
                 Key::Char('q') => return Ok(false),
                 Key::Char('i') => {
                     self.mode = Mode::Insert{
-                        entrance: self.raw_terminal.cursor_pos()?,
+                        entrance: self.cursor,
                     };
-                    write!(self.raw_terminal, "{}", termion::cursor::SteadyBar)?;
+                    write!(self.terminal, "{}", termion::cursor::SteadyBar)?;
+                }
+                Key::Char(':') => {
+                    self.mode = Mode::Command { buffer: String::new() };
+                    self.draw_command_line()?;
+                }
+                Key::Char('v') => {
+                    let anchor = self.cursor;
+                    self.mode = Mode::Visual { anchor };
+                    self.draw_visual_border(anchor)?;
                 }
                 _ => {}
             },
             Mode::Insert { entrance } => match key {
                 Key::Char('\n') => {
                     // On return, return to the column where insert mode entered
-                    let cursor_pos = self.raw_terminal.cursor_pos()?;
-                    write!(self.raw_terminal, "{}", termion::cursor::Goto(entrance.0, cursor_pos.1 + 1))?;
+                    let row = self.cursor.1;
+                    self.goto((entrance.0, row + 1))?;
+                }
+                Key::Char(c) => self.paint(&c.to_string())?,
+                Key::Ctrl('[') | Key::Esc => {
+                    self.mode = Mode::Normal;
+                    write!(self.terminal, "{}", termion::cursor::SteadyBlock)?;
+                }
+                Key::Backspace => {
+                    write!(self.terminal, "{}", termion::cursor::Left(1))?;
+                    self.cursor.0 = self.cursor.0.saturating_sub(1).max(1);
+                    self.paint(" ")?;
+                    write!(self.terminal, "{}", termion::cursor::Left(1))?;
+                    self.cursor.0 = self.cursor.0.saturating_sub(1).max(1);
+                }
+                _ => {}
+            }
+            Mode::Command { ref mut buffer } => match key {
+                Key::Char('\n') => {
+                    let command = buffer.clone();
+                    self.mode = Mode::Normal;
+                    self.clear_command_line()?;
+                    self.run_command(&command)?;
+                }
+                Key::Char(c) => {
+                    buffer.push(c);
+                    self.draw_command_line()?;
+                }
+                Key::Backspace => {
+                    buffer.pop();
+                    self.draw_command_line()?;
                 }
-                Key::Char(c) => write!(self.raw_terminal, "{}", c)?,
                 Key::Ctrl('[') | Key::Esc => {
                     self.mode = Mode::Normal;
-                    write!(self.raw_terminal, "{}", termion::cursor::SteadyBlock)?;
+                    self.clear_command_line()?;
+                }
+                _ => {}
+            }
+            Mode::Visual { anchor } => match key {
+                Key::Char('h') | Key::Char('l') | Key::Char('k') | Key::Char('j') |
+                Key::Char('H') | Key::Char('L') | Key::Char('K') | Key::Char('J') => {
+                    self.move_cursor(key)?;
+                    self.draw_visual_border(anchor)?;
+                }
+                Key::Char('f') | Key::Char('d') => {
+                    let brush = self.brush;
+                    self.fill_visual_rect(anchor, brush)?;
+                }
+                Key::Char(' ') | Key::Char('x') => self.fill_visual_rect(anchor, " ")?,
+                Key::Char('y') => self.yank_visual_rect(anchor)?,
+                Key::Char('p') => {
+                    self.restore_visual_border()?;
+                    self.paste_clipboard()?;
+                    self.draw_visual_border(anchor)?;
+                }
+                Key::Char('v') | Key::Ctrl('[') | Key::Esc => {
+                    self.restore_visual_border()?;
+                    self.mode = Mode::Normal;
                 }
-                Key::Backspace => write!(self.raw_terminal, "{} {}",
-                                         termion::cursor::Left(1),
-                                         termion::cursor::Left(1))?,
                 _ => {}
             }
         };
-        self.raw_terminal.flush()?;
+        self.terminal.flush()?;
+        Ok(true)
+    }
+
+    // Runs a command typed in the status line, e.g. "w board.txt" or "e board.txt".
+    // File I/O failures are reported on the status line instead of bubbling
+    // up and aborting the session, so a typo'd path can't cost the drawing.
+    fn run_command(&mut self, command: &str) -> Result<(), std::io::Error> {
+        let result = match parse_command(command) {
+            Some(("w", path)) => self.save_to_file(path),
+            Some(("e", path)) => self.load_from_file(path),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            self.show_status(&format!("error: {}", e))?;
+        }
+        Ok(())
+    }
+
+    fn draw_command_line(&mut self) -> Result<(), std::io::Error> {
+        let buffer = match &self.mode {
+            Mode::Command { buffer } => buffer.clone(),
+            _ => return Ok(()),
+        };
+        self.show_status(&format!(":{}", buffer))
+    }
+
+    fn clear_command_line(&mut self) -> Result<(), std::io::Error> {
+        self.show_status("")
+    }
+
+    // Overwrites the status line (the bottom row) with `message`, then puts
+    // the real cursor back where the app thinks it is.
+    fn show_status(&mut self, message: &str) -> Result<(), std::io::Error> {
+        let height = termion::terminal_size()?.1;
+        write!(self.terminal, "{}{}{}",
+               termion::cursor::Goto(1, height),
+               termion::clear::CurrentLine,
+               message)?;
+        write!(self.terminal, "{}", termion::cursor::Goto(self.cursor.0, self.cursor.1))?;
+        self.terminal.flush()?;
+        Ok(())
+    }
+
+    fn handle_mouse(&mut self, mouse_event: MouseEvent) -> Result<(), std::io::Error> {
+        match mouse_event {
+            // Click-and-drag paints the current brush glyph, like a whiteboard marker
+            MouseEvent::Press(MouseButton::Left, x, y) | MouseEvent::Hold(x, y) => {
+                self.goto((x, y))?;
+                let brush = self.brush;
+                self.paint(brush)?;
+            }
+            // Right-click erases
+            MouseEvent::Press(MouseButton::Right, x, y) => {
+                self.goto((x, y))?;
+                self.paint(" ")?;
+            }
+            _ => {}
+        }
+        self.terminal.flush()?;
+        Ok(())
+    }
+
+    fn handle_input(&mut self, event: Event) -> Result<bool, std::io::Error> {
+        match event {
+            Event::Key(key) => return self.handle_key(key),
+            Event::Mouse(mouse_event) => self.handle_mouse(mouse_event)?,
+            Event::Unsupported(_) => {}
+        }
         Ok(true)
     }
 
     fn clear_screen(&mut self) -> Result<(), std::io::Error> {
-        write!(self.raw_terminal, "{}{}",
-               termion::clear::All,
-               termion::cursor::Goto(1, 1))?;
-        self.raw_terminal.flush()?;
+        write!(self.terminal, "{}", termion::clear::All)?;
+        self.goto((1, 1))?;
+        self.terminal.flush()?;
+        self.canvas.clear();
         Ok(())
     }
 
-    fn run(&mut self) -> Result<(), std::io::Error> {
+    fn run(&mut self, initial_file: Option<&str>) -> Result<(), std::io::Error> {
         self.clear_screen()?;
-        self.show_manual()?;
-        for key in std::io::stdin().keys() {
-            if !self.handle_input(key?)? {
+        match initial_file {
+            Some(path) => self.load_from_file(path)?,
+            None => self.show_manual()?,
+        }
+        for event in std::io::stdin().events() {
+            if !self.handle_input(event?)? {
                 break
             }
         }
 
-        // Put cursor at end so the terminal prompt doesn't erase over the board
-        write!(self.raw_terminal, "{}", termion::cursor::Goto(10000, 10000))?;
-        self.raw_terminal.flush()?;
+        // Leaving the alternate screen (via Terminal's Drop) restores
+        // whatever the shell had on screen before we started.
         Ok(())
     }
 }
 
 // TODO:
-// - Better error reporting
-// - Revert terminal state even on error
-// - Don't clear the screen on exit
-//   - Look into termion alternative screen but
-//     make sure not to capture panic output
 // - Implement Ctrl-z for minimizing the GUI
-// - Add block visual mode
+// - Undo, using the canvas buffer
 fn main() -> Result<(), std::io::Error> {
-    Ok(App::new()?.run()?)
+    install_panic_hook();
+    let initial_file = std::env::args().nth(1);
+    App::new()?.run(initial_file.as_deref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canvas_round_trips_through_text() {
+        let mut canvas = Canvas::new();
+        canvas.set((1, 1), Cell { ch: 'a', ..Cell::default() });
+        canvas.set((3, 1), Cell { ch: 'b', ..Cell::default() });
+        canvas.set((2, 2), Cell { ch: 'c', ..Cell::default() });
+
+        let reloaded = Canvas::from_text(&canvas.to_text());
+
+        assert_eq!(reloaded.bounds(), canvas.bounds());
+        assert_eq!(reloaded.get((1, 1)).ch, 'a');
+        assert_eq!(reloaded.get((3, 1)).ch, 'b');
+        assert_eq!(reloaded.get((2, 2)).ch, 'c');
+    }
+
+    #[test]
+    fn load_cells_use_plain_white_never_an_external_pen() {
+        // `load_cells` takes no pen/App state at all, so it can't repeat
+        // the bug where `:e` after pressing a color key (e.g. `2` for red)
+        // repainted the whole loaded drawing in that color.
+        let cells = load_cells("ab\n");
+        assert_eq!(cells.len(), 2);
+        for (_, cell) in cells {
+            assert_eq!(cell.fg, color::Rgb(255, 255, 255));
+            assert_eq!(cell.bg, None);
+        }
+    }
+
+    #[test]
+    fn visual_rect_normalizes_any_drag_direction() {
+        assert_eq!(rect_of((5, 5), (2, 8)), ((2, 5), (5, 8)));
+        assert_eq!(rect_of((2, 8), (5, 5)), ((2, 5), (5, 8)));
+        assert_eq!(rect_of((4, 4), (4, 4)), ((4, 4), (4, 4)));
+    }
+
+    #[test]
+    fn parse_command_splits_verb_and_argument() {
+        assert_eq!(parse_command("w board.txt"), Some(("w", "board.txt")));
+        assert_eq!(parse_command("e path with spaces.txt"), Some(("e", "path with spaces.txt")));
+        assert_eq!(parse_command("w"), None);
+        assert_eq!(parse_command(""), None);
+    }
 }